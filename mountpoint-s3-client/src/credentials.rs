@@ -0,0 +1,447 @@
+use async_trait::async_trait;
+use std::env;
+use std::sync::RwLock;
+use std::time::Duration;
+use thiserror::Error;
+use time::OffsetDateTime;
+
+/// How long before a temporary credential's expiry we proactively refresh it, so that in-flight
+/// requests don't race an S3 401 caused by the clock ticking over between signing and sending.
+const REFRESH_BUFFER: Duration = Duration::from_secs(5 * 60);
+
+/// How long a failed fetch is remembered before [RefreshingCredentialsProvider] tries the inner
+/// provider again. Without this, a host where the inner provider can never succeed (e.g. IMDS
+/// unreachable because it isn't running on EC2) would retry the full round trip on every single
+/// S3 request.
+const NEGATIVE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// A set of AWS credentials, as returned by a [CredentialsProvider].
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+
+    /// When these credentials expire, if they are temporary. `None` means the credentials do not
+    /// expire (for example, long-lived static credentials).
+    pub expires_after: Option<OffsetDateTime>,
+}
+
+impl std::fmt::Debug for Credentials {
+    /// Manual [Debug] impl so we never accidentally log a secret access key or session token.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .field("session_token", &self.session_token.as_ref().map(|_| "<redacted>"))
+            .field("expires_after", &self.expires_after)
+            .finish()
+    }
+}
+
+/// Errors that can occur while resolving [Credentials] from a [CredentialsProvider].
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum CredentialsError {
+    /// This provider has no credentials to offer (for example, the relevant environment variables
+    /// aren't set). A [CredentialsProviderChain] treats this as "try the next provider".
+    #[error("no credentials available from this provider")]
+    NotAvailable,
+
+    /// The web identity token file was configured but could not be read.
+    #[error("failed to read web identity token file")]
+    TokenFileError(#[source] std::io::Error),
+
+    /// A request to a credentials endpoint (STS or the EC2 instance metadata service) failed.
+    #[error("request to {0} failed")]
+    RequestFailed(String, #[source] Box<dyn std::error::Error + Send + Sync>),
+
+    /// A response from a credentials endpoint could not be parsed.
+    #[error("failed to parse credentials response from {0}")]
+    ParseError(String),
+}
+
+/// A source of AWS [Credentials]. Implementations range from trivial (static credentials) to
+/// ones that fetch and cache temporary credentials from a remote endpoint.
+#[async_trait]
+pub trait CredentialsProvider: Send + Sync {
+    /// Provide a set of credentials, refreshing them if necessary.
+    async fn provide_credentials(&self) -> Result<Credentials, CredentialsError>;
+}
+
+/// A [CredentialsProvider] that always returns the same, fixed credentials.
+#[derive(Debug, Clone)]
+pub struct StaticCredentialsProvider {
+    credentials: Credentials,
+}
+
+impl StaticCredentialsProvider {
+    pub fn new(access_key_id: impl Into<String>, secret_access_key: impl Into<String>) -> Self {
+        Self {
+            credentials: Credentials {
+                access_key_id: access_key_id.into(),
+                secret_access_key: secret_access_key.into(),
+                session_token: None,
+                expires_after: None,
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for StaticCredentialsProvider {
+    async fn provide_credentials(&self) -> Result<Credentials, CredentialsError> {
+        Ok(self.credentials.clone())
+    }
+}
+
+/// A [CredentialsProvider] that reads `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, and (if set)
+/// `AWS_SESSION_TOKEN` from the process environment.
+#[derive(Debug, Default, Clone)]
+pub struct EnvironmentCredentialsProvider {
+    _private: (),
+}
+
+impl EnvironmentCredentialsProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for EnvironmentCredentialsProvider {
+    async fn provide_credentials(&self) -> Result<Credentials, CredentialsError> {
+        let access_key_id = env::var("AWS_ACCESS_KEY_ID").map_err(|_| CredentialsError::NotAvailable)?;
+        let secret_access_key = env::var("AWS_SECRET_ACCESS_KEY").map_err(|_| CredentialsError::NotAvailable)?;
+        let session_token = env::var("AWS_SESSION_TOKEN").ok();
+
+        Ok(Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token,
+            expires_after: None,
+        })
+    }
+}
+
+/// A [CredentialsProvider] that exchanges a web identity token (as used by Kubernetes service
+/// account projection / IRSA) for temporary credentials via STS `AssumeRoleWithWebIdentity`.
+///
+/// Configured from `AWS_WEB_IDENTITY_TOKEN_FILE` and `AWS_ROLE_ARN`, mirroring the AWS SDKs.
+#[derive(Debug, Clone)]
+pub struct WebIdentityCredentialsProvider {
+    token_file: String,
+    role_arn: String,
+    region: String,
+}
+
+impl WebIdentityCredentialsProvider {
+    pub fn new(token_file: impl Into<String>, role_arn: impl Into<String>, region: impl Into<String>) -> Self {
+        Self {
+            token_file: token_file.into(),
+            role_arn: role_arn.into(),
+            region: region.into(),
+        }
+    }
+
+    /// Construct a provider from the standard `AWS_WEB_IDENTITY_TOKEN_FILE` / `AWS_ROLE_ARN`
+    /// environment variables, if both are set.
+    pub fn from_env(region: impl Into<String>) -> Option<Self> {
+        let token_file = env::var("AWS_WEB_IDENTITY_TOKEN_FILE").ok()?;
+        let role_arn = env::var("AWS_ROLE_ARN").ok()?;
+        Some(Self::new(token_file, role_arn, region))
+    }
+
+    async fn fetch(&self) -> Result<Credentials, CredentialsError> {
+        let token = std::fs::read_to_string(&self.token_file).map_err(CredentialsError::TokenFileError)?;
+        let token = token.trim();
+
+        let endpoint = format!("https://sts.{}.amazonaws.com/", self.region);
+        let session_name = "mountpoint-s3";
+        let url = format!(
+            "{endpoint}?Action=AssumeRoleWithWebIdentity&Version=2011-06-15&RoleArn={}&RoleSessionName={session_name}&WebIdentityToken={}",
+            urlencode(&self.role_arn),
+            urlencode(token),
+        );
+
+        let body = reqwest::get(&url)
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| CredentialsError::RequestFailed(endpoint.clone(), Box::new(e)))?
+            .text()
+            .await
+            .map_err(|e| CredentialsError::RequestFailed(endpoint.clone(), Box::new(e)))?;
+
+        let access_key_id =
+            extract_xml_tag(&body, "AccessKeyId").ok_or_else(|| CredentialsError::ParseError(endpoint.clone()))?;
+        let secret_access_key =
+            extract_xml_tag(&body, "SecretAccessKey").ok_or_else(|| CredentialsError::ParseError(endpoint.clone()))?;
+        let session_token =
+            extract_xml_tag(&body, "SessionToken").ok_or_else(|| CredentialsError::ParseError(endpoint.clone()))?;
+        let expiration =
+            extract_xml_tag(&body, "Expiration").ok_or_else(|| CredentialsError::ParseError(endpoint.clone()))?;
+        let expires_after = OffsetDateTime::parse(&expiration, &time::format_description::well_known::Rfc3339)
+            .map_err(|_| CredentialsError::ParseError(endpoint))?;
+
+        Ok(Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token: Some(session_token),
+            expires_after: Some(expires_after),
+        })
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for WebIdentityCredentialsProvider {
+    async fn provide_credentials(&self) -> Result<Credentials, CredentialsError> {
+        self.fetch().await
+    }
+}
+
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/latest/api/token";
+const IMDS_ROLE_URL: &str = "http://169.254.169.254/latest/meta-data/iam/security-credentials/";
+const IMDS_TOKEN_TTL_SECONDS: &str = "21600";
+
+/// IMDS is link-local and should respond in milliseconds. A short timeout (matching the AWS
+/// SDKs' own IMDS default) keeps this provider from stalling every request for the OS TCP
+/// connect timeout on hosts that simply aren't running on EC2/EKS.
+const IMDS_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// A [CredentialsProvider] that fetches the role credentials attached to an EC2 instance profile
+/// from the instance metadata service, using the IMDSv2 session-token flow.
+#[derive(Debug, Default, Clone)]
+pub struct InstanceMetadataCredentialsProvider {
+    _private: (),
+}
+
+impl InstanceMetadataCredentialsProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    async fn fetch(&self) -> Result<Credentials, CredentialsError> {
+        let client = reqwest::Client::builder()
+            .timeout(IMDS_TIMEOUT)
+            .build()
+            .expect("reqwest client configuration is valid");
+
+        let token = client
+            .put(IMDS_TOKEN_URL)
+            .header("X-aws-ec2-metadata-token-ttl-seconds", IMDS_TOKEN_TTL_SECONDS)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| CredentialsError::RequestFailed(IMDS_TOKEN_URL.to_string(), Box::new(e)))?
+            .text()
+            .await
+            .map_err(|e| CredentialsError::RequestFailed(IMDS_TOKEN_URL.to_string(), Box::new(e)))?;
+
+        let role = client
+            .get(IMDS_ROLE_URL)
+            .header("X-aws-ec2-metadata-token", &token)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| CredentialsError::RequestFailed(IMDS_ROLE_URL.to_string(), Box::new(e)))?
+            .text()
+            .await
+            .map_err(|e| CredentialsError::RequestFailed(IMDS_ROLE_URL.to_string(), Box::new(e)))?;
+
+        let credentials_url = format!("{IMDS_ROLE_URL}{}", role.trim());
+        let body = client
+            .get(&credentials_url)
+            .header("X-aws-ec2-metadata-token", &token)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| CredentialsError::RequestFailed(credentials_url.clone(), Box::new(e)))?
+            .text()
+            .await
+            .map_err(|e| CredentialsError::RequestFailed(credentials_url.clone(), Box::new(e)))?;
+
+        let json: serde_json::Value =
+            serde_json::from_str(&body).map_err(|_| CredentialsError::ParseError(credentials_url.clone()))?;
+
+        let access_key_id = json["AccessKeyId"]
+            .as_str()
+            .ok_or_else(|| CredentialsError::ParseError(credentials_url.clone()))?
+            .to_string();
+        let secret_access_key = json["SecretAccessKey"]
+            .as_str()
+            .ok_or_else(|| CredentialsError::ParseError(credentials_url.clone()))?
+            .to_string();
+        let session_token = json["Token"]
+            .as_str()
+            .ok_or_else(|| CredentialsError::ParseError(credentials_url.clone()))?
+            .to_string();
+        let expiration = json["Expiration"]
+            .as_str()
+            .ok_or_else(|| CredentialsError::ParseError(credentials_url.clone()))?;
+        let expires_after = OffsetDateTime::parse(expiration, &time::format_description::well_known::Rfc3339)
+            .map_err(|_| CredentialsError::ParseError(credentials_url))?;
+
+        Ok(Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token: Some(session_token),
+            expires_after: Some(expires_after),
+        })
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for InstanceMetadataCredentialsProvider {
+    async fn provide_credentials(&self) -> Result<Credentials, CredentialsError> {
+        self.fetch().await
+    }
+}
+
+/// Wraps a [CredentialsProvider] of temporary credentials with a cache, so that repeated calls
+/// reuse the same credentials until shortly before they expire rather than hitting the network on
+/// every request.
+pub struct RefreshingCredentialsProvider<P> {
+    inner: P,
+    cached: RwLock<Option<Credentials>>,
+    /// When the inner provider's most recent fetch failed, so we can avoid hammering it again
+    /// within [NEGATIVE_CACHE_TTL].
+    last_failure: RwLock<Option<OffsetDateTime>>,
+}
+
+impl<P: CredentialsProvider> RefreshingCredentialsProvider<P> {
+    pub fn new(inner: P) -> Self {
+        Self {
+            inner,
+            cached: RwLock::new(None),
+            last_failure: RwLock::new(None),
+        }
+    }
+
+    fn needs_refresh(credentials: &Credentials) -> bool {
+        match credentials.expires_after {
+            Some(expires_after) => OffsetDateTime::now_utc() + REFRESH_BUFFER >= expires_after,
+            None => false,
+        }
+    }
+
+    fn recently_failed(&self) -> bool {
+        match *self.last_failure.read().unwrap() {
+            Some(failed_at) => OffsetDateTime::now_utc() < failed_at + NEGATIVE_CACHE_TTL,
+            None => false,
+        }
+    }
+}
+
+#[async_trait]
+impl<P: CredentialsProvider> CredentialsProvider for RefreshingCredentialsProvider<P> {
+    async fn provide_credentials(&self) -> Result<Credentials, CredentialsError> {
+        if let Some(credentials) = self.cached.read().unwrap().as_ref() {
+            if !Self::needs_refresh(credentials) {
+                return Ok(credentials.clone());
+            }
+        }
+
+        if self.recently_failed() {
+            return Err(CredentialsError::NotAvailable);
+        }
+
+        match self.inner.provide_credentials().await {
+            Ok(credentials) => {
+                *self.cached.write().unwrap() = Some(credentials.clone());
+                *self.last_failure.write().unwrap() = None;
+                Ok(credentials)
+            }
+            Err(err) => {
+                *self.last_failure.write().unwrap() = Some(OffsetDateTime::now_utc());
+                Err(err)
+            }
+        }
+    }
+}
+
+/// The default credentials provider chain, which tries each configured source in turn and uses
+/// the first one that successfully provides credentials:
+/// 1. Static credentials, if explicitly configured.
+/// 2. Environment variables ([EnvironmentCredentialsProvider]).
+/// 3. A web identity token file ([WebIdentityCredentialsProvider]), for EKS IAM roles for service
+///    accounts.
+/// 4. The EC2 instance metadata service ([InstanceMetadataCredentialsProvider]).
+///
+/// Only [CredentialsError::NotAvailable] moves on to the next provider; any other error from a
+/// provider ends the search and is returned as is.
+pub struct CredentialsProviderChain {
+    providers: Vec<Box<dyn CredentialsProvider>>,
+}
+
+impl CredentialsProviderChain {
+    /// Build the default provider chain for the given region (used to scope the STS endpoint for
+    /// web identity federation).
+    pub fn default_chain(region: impl Into<String>) -> Self {
+        let region = region.into();
+        let mut providers: Vec<Box<dyn CredentialsProvider>> = Vec::new();
+
+        providers.push(Box::new(EnvironmentCredentialsProvider::new()));
+
+        if let Some(provider) = WebIdentityCredentialsProvider::from_env(region) {
+            providers.push(Box::new(RefreshingCredentialsProvider::new(provider)));
+        }
+
+        providers.push(Box::new(RefreshingCredentialsProvider::new(
+            InstanceMetadataCredentialsProvider::new(),
+        )));
+
+        Self { providers }
+    }
+
+    /// Build a provider chain that tries the given static credentials before falling back to the
+    /// rest of the [default_chain](Self::default_chain).
+    pub fn with_static_credentials(
+        access_key_id: impl Into<String>,
+        secret_access_key: impl Into<String>,
+        region: impl Into<String>,
+    ) -> Self {
+        let mut chain = Self::default_chain(region);
+        chain
+            .providers
+            .insert(0, Box::new(StaticCredentialsProvider::new(access_key_id, secret_access_key)));
+        chain
+    }
+}
+
+#[async_trait]
+impl CredentialsProvider for CredentialsProviderChain {
+    async fn provide_credentials(&self) -> Result<Credentials, CredentialsError> {
+        for provider in &self.providers {
+            match provider.provide_credentials().await {
+                Ok(credentials) => return Ok(credentials),
+                Err(CredentialsError::NotAvailable) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(CredentialsError::NotAvailable)
+    }
+}
+
+/// Minimal URL percent-encoding for the query string values we send to STS. STS requires
+/// unreserved characters (`A-Za-z0-9-_.~`) to be left alone and everything else percent-encoded.
+fn urlencode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// Pull the text content out of the first `<tag>...</tag>` in an XML document. STS responses are
+/// simple enough that a full XML parser isn't warranted.
+fn extract_xml_tag(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&close)? + start;
+    Some(xml[start..end].to_string())
+}
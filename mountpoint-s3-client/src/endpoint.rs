@@ -0,0 +1,175 @@
+//! Configuration for where requests are sent, so an [crate::ObjectClient] can talk to AWS S3,
+//! a custom region, or an S3-compatible service (e.g. MinIO, Garage) at its own endpoint.
+
+use crate::signing::host_header;
+
+/// The scheme to use when talking to an endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl Scheme {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        }
+    }
+
+    fn default_port(&self) -> u16 {
+        match self {
+            Scheme::Http => 80,
+            Scheme::Https => 443,
+        }
+    }
+}
+
+/// Describes where to send requests and how to address buckets within them.
+///
+/// By default, requests are sent to AWS S3 using virtual-hosted-style addressing
+/// (`https://<bucket>.s3.<region>.amazonaws.com/<key>`). Set a custom `host` (and optionally
+/// `port`/`scheme`) to target an S3-compatible service instead, and set `force_path_style` if that
+/// service doesn't support virtual-hosted-style addressing.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct EndpointConfig {
+    pub scheme: Scheme,
+    /// A custom endpoint host, e.g. `"minio.example.com"`. `None` means the default AWS S3 host
+    /// for `region`.
+    pub host: Option<String>,
+    /// A custom port. `None` means the default port for `scheme`.
+    pub port: Option<u16>,
+    /// The region used both to derive the default AWS host and as the SigV4 signing region.
+    pub region: String,
+    /// Address buckets as `http://host/<bucket>/<key>` instead of `http://<bucket>.host/<key>`.
+    /// Required for most non-AWS S3-compatible services.
+    pub force_path_style: bool,
+}
+
+impl EndpointConfig {
+    /// A default configuration pointing at AWS S3 in the given region, using virtual-hosted-style
+    /// addressing.
+    pub fn new(region: impl Into<String>) -> Self {
+        Self {
+            scheme: Scheme::Https,
+            host: None,
+            port: None,
+            region: region.into(),
+            force_path_style: false,
+        }
+    }
+
+    /// Point at a custom endpoint, e.g. a self-hosted MinIO or Garage instance.
+    pub fn with_endpoint(mut self, scheme: Scheme, host: impl Into<String>, port: Option<u16>) -> Self {
+        self.scheme = scheme;
+        self.host = Some(host.into());
+        self.port = port;
+        self
+    }
+
+    pub fn with_path_style(mut self, force_path_style: bool) -> Self {
+        self.force_path_style = force_path_style;
+        self
+    }
+
+    fn default_host(&self) -> String {
+        format!("s3.{}.amazonaws.com", self.region)
+    }
+
+    /// Resolve the host, port, and URI path to use for a request against `bucket`/`key`.
+    pub fn resolve(&self, bucket: &str, key: &str) -> ResolvedEndpoint {
+        let host = self.host.clone().unwrap_or_else(|| self.default_host());
+        let port = self.port.unwrap_or_else(|| self.scheme.default_port());
+
+        let (request_host, path) = if self.force_path_style {
+            (host, format!("/{bucket}/{key}"))
+        } else {
+            (format!("{bucket}.{host}"), format!("/{key}"))
+        };
+
+        ResolvedEndpoint {
+            scheme: self.scheme,
+            host_header: host_header(&request_host, Some(port), self.scheme.default_port()),
+            host: request_host,
+            port,
+            path,
+        }
+    }
+}
+
+/// The concrete host/port/path a request should be sent to, after resolving an [EndpointConfig]
+/// for a particular bucket and key.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ResolvedEndpoint {
+    pub scheme: Scheme,
+    /// The host requests should connect to.
+    pub host: String,
+    /// The value to send in the `Host` header (includes the port when it's non-default).
+    pub host_header: String,
+    pub port: u16,
+    /// The URI path of the request, e.g. `/key` or `/bucket/key` depending on addressing style.
+    pub path: String,
+}
+
+impl ResolvedEndpoint {
+    /// The full base URL for this request, always including the port, e.g.
+    /// `https://bucket.s3.us-east-1.amazonaws.com:443`.
+    pub fn url(&self) -> String {
+        format!("{}://{}:{}", self.scheme.as_str(), self.host, self.port)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn virtual_hosted_style_resolves_bucket_into_host() {
+        let config = EndpointConfig::new("us-east-1");
+        let resolved = config.resolve("my-bucket", "a/b/c.txt");
+
+        assert_eq!(resolved.host, "my-bucket.s3.us-east-1.amazonaws.com");
+        assert_eq!(resolved.host_header, "my-bucket.s3.us-east-1.amazonaws.com");
+        assert_eq!(resolved.path, "/a/b/c.txt");
+        assert_eq!(resolved.port, 443);
+    }
+
+    #[test]
+    fn path_style_addresses_bucket_in_the_path() {
+        let config = EndpointConfig::new("us-east-1").with_path_style(true);
+        let resolved = config.resolve("my-bucket", "a/b/c.txt");
+
+        assert_eq!(resolved.host, "s3.us-east-1.amazonaws.com");
+        assert_eq!(resolved.host_header, "s3.us-east-1.amazonaws.com");
+        assert_eq!(resolved.path, "/my-bucket/a/b/c.txt");
+    }
+
+    #[test]
+    fn custom_endpoint_with_non_default_port_is_path_style() {
+        let config = EndpointConfig::new("garage")
+            .with_endpoint(Scheme::Http, "localhost", Some(3900))
+            .with_path_style(true);
+        let resolved = config.resolve("my-bucket", "key");
+
+        assert_eq!(resolved.scheme, Scheme::Http);
+        assert_eq!(resolved.host, "localhost");
+        // The port must show up in the Host header since it isn't the scheme's default.
+        assert_eq!(resolved.host_header, "localhost:3900");
+        assert_eq!(resolved.port, 3900);
+        assert_eq!(resolved.path, "/my-bucket/key");
+    }
+
+    #[test]
+    fn custom_endpoint_on_default_port_omits_port_from_host_header() {
+        let config = EndpointConfig::new("garage")
+            .with_endpoint(Scheme::Https, "minio.example.com", Some(443))
+            .with_path_style(true);
+        let resolved = config.resolve("my-bucket", "key");
+
+        assert_eq!(resolved.host, "minio.example.com");
+        assert_eq!(resolved.host_header, "minio.example.com");
+    }
+}
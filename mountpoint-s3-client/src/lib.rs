@@ -0,0 +1,9 @@
+mod credentials;
+mod endpoint;
+mod object_client;
+mod signing;
+
+pub use credentials::*;
+pub use endpoint::*;
+pub use object_client::*;
+pub use signing::*;
@@ -1,7 +1,9 @@
 use async_trait::async_trait;
 use auto_impl::auto_impl;
-use futures::Stream;
+use futures::stream::{self, Stream};
+use std::collections::VecDeque;
 use std::ops::Range;
+use std::sync::Arc;
 use thiserror::Error;
 use time::OffsetDateTime;
 
@@ -60,6 +62,71 @@ pub trait ObjectClient {
         params: &PutObjectParams,
         contents: impl Stream<Item = impl AsRef<[u8]> + Send> + Send,
     ) -> ObjectClientResult<PutObjectResult, PutObjectError, Self::ClientError>;
+
+    /// Start a new multipart upload, returning an upload ID that must be passed to subsequent
+    /// [ObjectClient::upload_part], [ObjectClient::complete_multipart_upload], and
+    /// [ObjectClient::abort_multipart_upload] calls for this upload.
+    async fn create_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<CreateMultipartUploadResult, CreateMultipartUploadError, Self::ClientError>;
+
+    /// Upload a single part of a multipart upload previously started with
+    /// [ObjectClient::create_multipart_upload]. Every part except the last must be at least 5
+    /// MiB, and `part_number` must be in the range `1..=10000`.
+    async fn upload_part(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        part_number: u16,
+        contents: impl Stream<Item = impl AsRef<[u8]> + Send> + Send,
+    ) -> ObjectClientResult<UploadPartResult, UploadPartError, Self::ClientError>;
+
+    /// Complete a multipart upload, assembling the given parts (in ascending `part_number` order,
+    /// with the [UploadPartResult::etag]s returned by [ObjectClient::upload_part]) into a single
+    /// object.
+    async fn complete_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<CompletedPart>,
+    ) -> ObjectClientResult<CompleteMultipartUploadResult, CompleteMultipartUploadError, Self::ClientError>;
+
+    /// Abort a multipart upload, discarding any parts already uploaded. Callers should abort an
+    /// upload whenever they cannot complete it, since S3 otherwise continues to bill for the
+    /// uploaded parts until they are cleaned up.
+    async fn abort_multipart_upload(
+        &self,
+        bucket: &str,
+        key: &str,
+        upload_id: &str,
+    ) -> ObjectClientResult<AbortMultipartUploadResult, AbortMultipartUploadError, Self::ClientError>;
+
+    /// Copy an object to a new bucket/key entirely server-side, without downloading or
+    /// re-uploading its contents.
+    async fn copy_object(
+        &self,
+        source_bucket: &str,
+        source_key: &str,
+        dest_bucket: &str,
+        dest_key: &str,
+        params: &PutObjectParams,
+    ) -> ObjectClientResult<CopyObjectResult, CopyObjectError, Self::ClientError>;
+
+    /// Delete a batch of objects from the object store in as few round trips as possible.
+    ///
+    /// Implementations must issue one multi-object delete request per up-to-1000 keys, chunking
+    /// `keys` automatically when there are more than that. The returned [DeleteObjectsResult]
+    /// reports each key that was deleted and any per-key errors across all of the chunks.
+    async fn delete_objects(
+        &self,
+        bucket: &str,
+        keys: &[&str],
+    ) -> ObjectClientResult<DeleteObjectsResult, DeleteObjectsError, Self::ClientError>;
 }
 
 /// Errors returned by calls to an [ObjectClient]. Errors that are explicitly modeled on a
@@ -121,6 +188,83 @@ pub enum ListObjectsError {
     NoSuchBucket,
 }
 
+/// A single entry yielded by [list_objects_stream]: either an object or a common prefix rolled up
+/// by the delimiter.
+#[derive(Debug)]
+pub enum ListObjectsStreamItem {
+    Object(ObjectInfo),
+    CommonPrefix(String),
+}
+
+/// Build an auto-paginating stream over every [ListObjectsStreamItem] under `prefix`, issuing as
+/// many [ObjectClient::list_objects] pages as needed and following `next_continuation_token` until
+/// the listing is exhausted. This is the adaptor `opendir`/`readdir`-style callers should build on
+/// instead of handling `next_continuation_token` themselves, so a directory with more than
+/// `max_keys` entries doesn't silently truncate.
+pub fn list_objects_stream<C>(
+    client: Arc<C>,
+    bucket: &str,
+    delimiter: &str,
+    max_keys: usize,
+    prefix: &str,
+) -> impl Stream<Item = ObjectClientResult<ListObjectsStreamItem, ListObjectsError, C::ClientError>>
+where
+    C: ObjectClient + Send + Sync + 'static,
+{
+    struct State<C> {
+        client: Arc<C>,
+        bucket: String,
+        prefix: String,
+        delimiter: String,
+        max_keys: usize,
+        buffered: VecDeque<ListObjectsStreamItem>,
+        continuation_token: Option<String>,
+        done: bool,
+    }
+
+    let state = State {
+        client,
+        bucket: bucket.to_string(),
+        prefix: prefix.to_string(),
+        delimiter: delimiter.to_string(),
+        max_keys,
+        buffered: VecDeque::new(),
+        continuation_token: None,
+        done: false,
+    };
+
+    stream::try_unfold(state, |mut state| async move {
+        loop {
+            if let Some(item) = state.buffered.pop_front() {
+                return Ok(Some((item, state)));
+            }
+            if state.done {
+                return Ok(None);
+            }
+
+            let result = state
+                .client
+                .list_objects(
+                    &state.bucket,
+                    state.continuation_token.as_deref(),
+                    &state.delimiter,
+                    state.max_keys,
+                    &state.prefix,
+                )
+                .await?;
+
+            state.continuation_token = result.next_continuation_token;
+            state.done = state.continuation_token.is_none();
+            state
+                .buffered
+                .extend(result.objects.into_iter().map(ListObjectsStreamItem::Object));
+            state
+                .buffered
+                .extend(result.common_prefixes.into_iter().map(ListObjectsStreamItem::CommonPrefix));
+        }
+    })
+}
+
 /// Result of a [ObjectClient::head_object] request
 #[derive(Debug)]
 #[non_exhaustive]
@@ -175,6 +319,120 @@ pub enum PutObjectError {
     NoSuchBucket,
 }
 
+/// Result of a [ObjectClient::create_multipart_upload] request
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct CreateMultipartUploadResult {
+    /// The upload ID identifying this multipart upload to subsequent part/complete/abort calls.
+    pub upload_id: String,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CreateMultipartUploadError {
+    #[error("The bucket does not exist")]
+    NoSuchBucket,
+}
+
+/// Result of a [ObjectClient::upload_part] request
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct UploadPartResult {
+    /// Entity tag of the uploaded part, which must be passed back in the corresponding
+    /// [CompletedPart] when completing the upload.
+    pub etag: String,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum UploadPartError {
+    #[error("The multipart upload does not exist")]
+    NoSuchUpload,
+
+    #[error("The part number was not between 1 and 10000")]
+    InvalidPartNumber,
+}
+
+/// A single part of a multipart upload, as passed to [ObjectClient::complete_multipart_upload].
+#[derive(Debug, Clone)]
+pub struct CompletedPart {
+    /// The number of this part within the upload, in the range `1..=10000`.
+    pub part_number: u16,
+
+    /// The entity tag returned by the [ObjectClient::upload_part] call for this part.
+    pub etag: String,
+}
+
+/// Result of a [ObjectClient::complete_multipart_upload] request
+/// TODO: Populate this struct with return fields from the S3 API, e.g., etag.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct CompleteMultipartUploadResult {}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CompleteMultipartUploadError {
+    #[error("The multipart upload does not exist")]
+    NoSuchUpload,
+
+    #[error("The parts list was empty, out of order, or referenced an invalid part number")]
+    InvalidPart,
+}
+
+/// Result of a [ObjectClient::abort_multipart_upload] request
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct AbortMultipartUploadResult {}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AbortMultipartUploadError {
+    #[error("The multipart upload does not exist")]
+    NoSuchUpload,
+}
+
+/// Result of a [ObjectClient::copy_object] request
+/// TODO: Populate this struct with return fields from the S3 API, e.g., etag.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct CopyObjectResult {}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CopyObjectError {
+    #[error("The bucket does not exist")]
+    NoSuchBucket,
+
+    #[error("The key does not exist")]
+    NoSuchKey,
+}
+
+/// Result of a [ObjectClient::delete_objects] request
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct DeleteObjectsResult {
+    /// Keys that were successfully deleted.
+    pub deleted: Vec<String>,
+
+    /// Per-key errors, for keys that could not be deleted.
+    pub errors: Vec<DeleteObjectsEntryError>,
+}
+
+/// An error deleting a single key within a [ObjectClient::delete_objects] request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteObjectsEntryError {
+    pub key: String,
+    pub code: String,
+    pub message: String,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DeleteObjectsError {
+    #[error("The bucket does not exist")]
+    NoSuchBucket,
+}
+
 /// Metadata about a single S3 object.
 /// See https://docs.aws.amazon.com/AmazonS3/latest/API/API_Object.html for more details.
 #[derive(Debug)]
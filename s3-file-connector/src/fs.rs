@@ -1,4 +1,5 @@
 use async_trait::async_trait;
+use futures::{pin_mut, stream, StreamExt};
 use std::collections::{HashMap};
 use std::ffi::OsStr;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -7,9 +8,10 @@ use std::time::{Duration, UNIX_EPOCH, SystemTime};
 use tracing::{error, trace};
 
 use fuser::{
-    FileAttr, FileType, Filesystem, KernelConfig, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry, ReplyOpen, Request,
+    FileAttr, FileType, Filesystem, KernelConfig, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyOpen, ReplyWrite, Request,
 };
-use s3_client::{S3Client, StreamingGetObject};
+use s3_client::{list_objects_stream, CompletedPart, ListObjectsStreamItem, PutObjectParams, S3Client, StreamingGetObject};
 
 // FIXME Use newtype here? Will add a bunch of .into()s...
 type Inode = u64;
@@ -65,22 +67,67 @@ impl InodeInfo {
 
 const BLOCK_SIZE: u64 = 4096;
 
+/// Size of the chunks we buffer incoming FUSE writes into before flushing them as multipart
+/// upload parts. Must be at least 5 MiB (the minimum S3 multipart part size, other than the
+/// final part).
+const WRITE_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// How long a directory listing stays valid before `opendir` re-LISTs the prefix, so repeated
+/// `ls`-like traffic against the same directory doesn't hit S3 (or re-allocate inodes) every time.
+const DIR_CACHE_TTL: Duration = Duration::from_secs(1);
+
+/// The page size requested from each underlying `ListObjectsV2` call within [list_objects_stream];
+/// the stream transparently follows `next_continuation_token` beyond this.
+const LIST_MAX_KEYS: usize = 1000;
+
 #[derive(Clone, Debug)]
 struct DirHandle {
     children: Vec<Inode>,
 }
 
+/// Per-file-handle state for a write-back multipart upload in progress.
+#[derive(Debug)]
+struct WriteHandle {
+    bucket: String,
+    key: String,
+    upload_id: Option<String>,
+    buffer: Vec<u8>,
+    next_part_number: u16,
+    completed_parts: Vec<CompletedPart>,
+    size: u64,
+}
+
+impl WriteHandle {
+    fn new(bucket: String, key: String) -> Self {
+        Self {
+            bucket,
+            key,
+            upload_id: None,
+            buffer: Vec::with_capacity(WRITE_CHUNK_SIZE),
+            next_part_number: 1,
+            completed_parts: Vec::new(),
+            size: 0,
+        }
+    }
+}
+
 pub struct S3Filesystem {
     client: Arc<S3Client>,
     bucket: String,
     key: String,
     size: usize,
     inflight_reads: RwLock<HashMap<u64, Mutex<StreamingGetObject>>>,
+    inflight_writes: RwLock<HashMap<u64, Mutex<WriteHandle>>>,
     next_handle: AtomicU64,
     next_inode: AtomicU64,
     inode_info: RwLock<HashMap<Inode, InodeInfo>>,
     dir_handles: RwLock<HashMap<u64, DirHandle>>,
     dir_entries: RwLock<HashMap<Inode, Arc<RwLock<HashMap<String, Inode>>>>>,
+    /// When each directory inode was last listed, used to serve `opendir` from
+    /// [Self::dir_children_cache] instead of re-LISTing within [DIR_CACHE_TTL].
+    dir_listed_at: RwLock<HashMap<Inode, SystemTime>>,
+    /// The children allocated for a directory inode the last time it was listed.
+    dir_children_cache: RwLock<HashMap<Inode, Vec<Inode>>>,
 }
 
 impl S3Filesystem {
@@ -107,12 +154,76 @@ impl S3Filesystem {
             key: key.to_string(),
             size,
             inflight_reads: Default::default(),
+            inflight_writes: Default::default(),
             next_handle: AtomicU64::new(1),
             next_inode: AtomicU64::new(ROOT_INODE + 1), // next Inode to allocate
             inode_info: RwLock::new(inode_info),
             dir_handles: RwLock::new(HashMap::new()),
             dir_entries: RwLock::new(dir_entries),
+            dir_listed_at: Default::default(),
+            dir_children_cache: Default::default(),
+        }
+    }
+
+    /// List the full contents of the directory `parent` (whose key prefix is `prefix`),
+    /// consuming the auto-paginating [list_objects_stream] so directories with more entries than
+    /// a single `ListObjectsV2` page don't silently truncate, allocating a fresh inode for each
+    /// entry and updating `inode_info`/`dir_entries` accordingly. Returns the inodes allocated, in
+    /// listing order.
+    async fn list_dir(&self, parent: Inode, prefix: &str) -> Result<Vec<Inode>, libc::c_int> {
+        let mut new_map = HashMap::new();
+        let mut new_inodes = Vec::new();
+
+        let entries = list_objects_stream(Arc::clone(&self.client), &self.bucket, "/", LIST_MAX_KEYS, prefix);
+        pin_mut!(entries);
+
+        while let Some(item) = entries.next().await {
+            let (name, kind, size) = match item {
+                Ok(ListObjectsStreamItem::Object(object)) => {
+                    debug_assert!(object.key.starts_with(prefix));
+                    (object.key[prefix.len()..].to_string(), FileType::RegularFile, object.size)
+                }
+                Ok(ListObjectsStreamItem::CommonPrefix(mut common_prefix)) => {
+                    // Common prefixes end in the delimiter, which we strip to get the
+                    // directory's own name.
+                    assert_eq!(common_prefix.pop(), Some('/'));
+                    debug_assert!(common_prefix.starts_with(prefix));
+                    (common_prefix[prefix.len()..].to_string(), FileType::Directory, 0)
+                }
+                Err(err) => {
+                    error!(?err, "ListObjectsV2 failed");
+                    return Err(libc::EIO);
+                }
+            };
+
+            // FIXME Fix ObjectInfo to also return object mtime and return that here
+            let mtime = UNIX_EPOCH;
+            let info = InodeInfo::new(name.clone(), parent, mtime, kind, size);
+            let ino = self.next_inode();
+            self.inode_info.write().unwrap().insert(ino, info);
+            new_inodes.push(ino);
+
+            new_map.insert(name, ino);
+        }
+
+        let mut dir_entries = self.dir_entries.write().unwrap();
+        let _old_map = dir_entries.insert(parent, Arc::new(RwLock::new(new_map)));
+        drop(dir_entries);
+
+        // FIXME We could garbage collect old inodes from the inode table as below
+        //  but that would break any concurrent filesystem calls that were accessing the previous inode
+        /*
+        if let Some(old_map) = old_map {
+            let mut inode_info = self.inode_info.write().unwrap();
+            for (_, ino) in old_map.write().unwrap().drain() {
+                if ino != ROOT_INODE { // Because / has entries for . and ..
+                    assert!(inode_info.remove(&ino).is_some());
+                }
+            }
         }
+        */
+
+        Ok(new_inodes)
     }
 
     fn path_from_root(&self, mut ino: Inode) -> Option<String> {
@@ -226,6 +337,47 @@ impl Filesystem for S3Filesystem {
         reply.opened(fh, 0);
     }
 
+    async fn create(
+        &self,
+        _req: &Request<'_>,
+        parent: Inode,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        trace!("fs:create with parent {:?} name {:?}", parent, name);
+
+        let name = name.to_str().unwrap().to_string();
+        let mtime = SystemTime::now();
+        let info = InodeInfo::new(name.clone(), parent, mtime, FileType::RegularFile, 0);
+        let ino = self.next_inode();
+
+        {
+            let mut inode_info = self.inode_info.write().unwrap();
+            inode_info.insert(ino, info.clone());
+        }
+
+        let parent_entries = {
+            let mut dir_entries = self.dir_entries.write().unwrap();
+            Arc::clone(
+                dir_entries
+                    .entry(parent)
+                    .or_insert_with(|| Arc::new(RwLock::new(HashMap::new()))),
+            )
+        };
+        parent_entries.write().unwrap().insert(name, ino);
+
+        // A newly-created file didn't come from a listing, so there's nothing stale to purge, but
+        // the parent's cached children are now missing this entry until the next `opendir`.
+        self.dir_listed_at.write().unwrap().remove(&parent);
+        self.dir_children_cache.write().unwrap().remove(&parent);
+
+        let fh = self.next_handle();
+        reply.created(&TTL_ZERO, &make_attr(ino, &info), 0, fh, 0);
+    }
+
     async fn read(
         &self,
         _req: &Request<'_>,
@@ -270,65 +422,31 @@ impl Filesystem for S3Filesystem {
             }
         };
 
-        let children = match self.client.list_objects_v2(&self.bucket, &prefix, "/", None).await {
-            Ok(result) => result.objects,
-            Err(err) => {
-                error!(?err, "ListObjectsV2 failed");
-                reply.error(libc::EIO);
-                return;
+        let now = SystemTime::now();
+        let cached = {
+            let listed_at = self.dir_listed_at.read().unwrap();
+            match listed_at.get(&parent) {
+                Some(&t) if now.duration_since(t).unwrap_or(Duration::MAX) < DIR_CACHE_TTL => {
+                    self.dir_children_cache.read().unwrap().get(&parent).cloned()
+                }
+                _ => None,
             }
         };
 
-        // FIXME
-        //   For now we're going to issue a LIST on every opendir to keep it simple and not
-        //   try and cache directory entries. This means children will get allocated fresh
-        //   inode numbers on each opendir.
-        let mut new_map = HashMap::new();
-        let mut inode_info = self.inode_info.write().unwrap();
-        let mut new_inodes = Vec::new();
-
-        for child in children {
-            let (name, kind) = if !child.key.is_empty() { // an object
-                (child.key.into_string().unwrap(), FileType::RegularFile)
-            } else {
-                // unwrap is okay because S3 keys are UTF-8
-                let mut str = child.prefix.into_string().unwrap();
-                assert_eq!(str.pop(), Some('/'));
-                (str, FileType::Directory)
-            };
-
-            debug_assert!(name.starts_with(&prefix));
-
-            let name = name[prefix.len()..].to_string();
-
-            // FIXME Fix S3Client's list_objects_v2 to also return object mtime
-            // FIXME and return that here
-            let mtime = UNIX_EPOCH;
-            let info = InodeInfo::new(name.clone(), parent, mtime, kind, child.size);
-            let ino = self.next_inode();
-            inode_info.insert(ino, info);
-            new_inodes.push(ino);
-
-            new_map.insert(name, ino);
-        }
-        drop(inode_info);
-
-        let mut dir_entries = self.dir_entries.write().unwrap();
-        let _old_map = dir_entries.insert(parent, Arc::new(RwLock::new(new_map)));
-        drop(dir_entries);
-
-        // FIXME We could garbage collect old inodes from the inode table as below
-        //  but that would break any concurrent filesystem calls that were accessing the previous inode
-        /*
-        if let Some(old_map) = old_map {
-            let mut inode_info = self.inode_info.write().unwrap();
-            for (_, ino) in old_map.write().unwrap().drain() {
-                if ino != ROOT_INODE { // Because / has entries for . and ..
-                    assert!(inode_info.remove(&ino).is_some());
+        let new_inodes = match cached {
+            Some(new_inodes) => new_inodes,
+            None => match self.list_dir(parent, &prefix).await {
+                Ok(new_inodes) => {
+                    self.dir_listed_at.write().unwrap().insert(parent, now);
+                    self.dir_children_cache.write().unwrap().insert(parent, new_inodes.clone());
+                    new_inodes
                 }
-            }
-        }
-        */
+                Err(errno) => {
+                    reply.error(errno);
+                    return;
+                }
+            },
+        };
 
         // Allocate a handle
         let fh = self.next_handle();
@@ -367,4 +485,305 @@ impl Filesystem for S3Filesystem {
 
         reply.ok();
     }
+
+    async fn write(
+        &self,
+        _req: &Request<'_>,
+        ino: Inode,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        trace!("fs:write with ino {:?} fh {:?} offset {:?} len {:?}", ino, fh, offset, data.len());
+
+        // We only support sequential, append-only writes (the only pattern FUSE clients like `cp`
+        // and `dd` produce), since S3 multipart upload parts must be uploaded in order.
+        let current_size = {
+            let inflight_writes = self.inflight_writes.read().unwrap();
+            inflight_writes.get(&fh).map(|handle| handle.lock().unwrap().size)
+        };
+        if current_size.is_none() {
+            if offset != 0 {
+                error!(ino, fh, offset, "fs:write first write to a handle must start at offset 0");
+                reply.error(libc::EINVAL);
+                return;
+            }
+            let key = match self.path_from_root(ino) {
+                Some(path) => path.trim_start_matches('/').to_string(),
+                None => {
+                    reply.error(libc::ENOENT);
+                    return;
+                }
+            };
+            let mut inflight_writes = self.inflight_writes.write().unwrap();
+            inflight_writes
+                .entry(fh)
+                .or_insert_with(|| Mutex::new(WriteHandle::new(self.bucket.clone(), key)));
+        } else if current_size != Some(offset as u64) {
+            error!(ino, fh, offset, "fs:write only supports sequential writes");
+            reply.error(libc::EINVAL);
+            return;
+        }
+
+        // Take ownership of this handle's state out of the map, the same way `release` does via
+        // `Mutex::into_inner`, so we don't hold a `MutexGuard` (which is `!Send`) across the
+        // `.await`s below: this `Filesystem` impl is a plain `#[async_trait]`, so its futures must
+        // be `Send`.
+        let mut handle = {
+            let mut inflight_writes = self.inflight_writes.write().unwrap();
+            inflight_writes.remove(&fh).unwrap().into_inner().unwrap()
+        };
+
+        if handle.upload_id.is_none() {
+            let params = PutObjectParams::default();
+            match self
+                .client
+                .create_multipart_upload(&handle.bucket, &handle.key, &params)
+                .await
+            {
+                Ok(result) => handle.upload_id = Some(result.upload_id),
+                Err(err) => {
+                    error!(?err, "CreateMultipartUpload failed");
+                    self.inflight_writes.write().unwrap().insert(fh, Mutex::new(handle));
+                    reply.error(libc::EIO);
+                    return;
+                }
+            }
+        }
+
+        handle.buffer.extend_from_slice(data);
+        handle.size += data.len() as u64;
+
+        while handle.buffer.len() >= WRITE_CHUNK_SIZE {
+            let chunk: Vec<u8> = handle.buffer.drain(..WRITE_CHUNK_SIZE).collect();
+            if let Err(err) = self.upload_part(&mut handle, chunk).await {
+                error!(?err, "UploadPart failed");
+                self.inflight_writes.write().unwrap().insert(fh, Mutex::new(handle));
+                reply.error(libc::EIO);
+                return;
+            }
+        }
+
+        self.inflight_writes.write().unwrap().insert(fh, Mutex::new(handle));
+        reply.written(data.len() as u32);
+    }
+
+    async fn release(
+        &self,
+        _req: &Request<'_>,
+        ino: Inode,
+        fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        trace!("fs:release with ino {:?} fh {:?}", ino, fh);
+
+        let handle = {
+            let mut inflight_writes = self.inflight_writes.write().unwrap();
+            inflight_writes.remove(&fh)
+        };
+
+        let mut handle = match handle {
+            Some(handle) => handle.into_inner().unwrap(),
+            None => {
+                reply.ok();
+                return;
+            }
+        };
+
+        if handle.upload_id.is_none() {
+            // No multipart upload was ever started, e.g. a `create()`d file closed without any
+            // `write()` call. S3 has no notion of an empty object to multipart-upload into, so
+            // put an empty object directly instead of leaving the file existing only in our
+            // in-memory maps until the next listing makes it disappear.
+            let params = PutObjectParams::default();
+            if let Err(err) = self
+                .client
+                .put_object(&handle.bucket, &handle.key, &params, stream::empty::<Vec<u8>>())
+                .await
+            {
+                error!(?err, "PutObject failed for empty file");
+                reply.error(libc::EIO);
+                return;
+            }
+            reply.ok();
+            return;
+        }
+
+        if !handle.buffer.is_empty() {
+            let chunk = std::mem::take(&mut handle.buffer);
+            if let Err(err) = self.upload_part(&mut handle, chunk).await {
+                error!(?err, "UploadPart failed during release");
+                self.abort_write(&handle).await;
+                reply.error(libc::EIO);
+                return;
+            }
+        }
+
+        let upload_id = handle.upload_id.clone().unwrap();
+        let parts = handle.completed_parts.clone();
+        match self
+            .client
+            .complete_multipart_upload(&handle.bucket, &handle.key, &upload_id, parts)
+            .await
+        {
+            Ok(_) => {
+                let mut inode_info = self.inode_info.write().unwrap();
+                if let Some(info) = inode_info.get_mut(&ino) {
+                    info.size = handle.size;
+                }
+                reply.ok();
+            }
+            Err(err) => {
+                error!(?err, "CompleteMultipartUpload failed");
+                self.abort_write(&handle).await;
+                reply.error(libc::EIO);
+            }
+        }
+    }
+
+    async fn rename(
+        &self,
+        _req: &Request<'_>,
+        parent: Inode,
+        name: &OsStr,
+        newparent: Inode,
+        newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        trace!(
+            "fs:rename with parent {:?} name {:?} newparent {:?} newname {:?}",
+            parent,
+            name,
+            newparent,
+            newname
+        );
+
+        let name = name.to_str().unwrap().to_string();
+        let newname = newname.to_str().unwrap().to_string();
+
+        let ino = {
+            let dir_entries = self.dir_entries.read().unwrap();
+            match dir_entries.get(&parent).and_then(|entries| entries.read().unwrap().get(&name).copied()) {
+                Some(ino) => ino,
+                None => {
+                    reply.error(libc::ENOENT);
+                    return;
+                }
+            }
+        };
+
+        let source_key = match self.path_from_root(ino) {
+            Some(path) => path.trim_start_matches('/').to_string(),
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        let dest_prefix = match self.path_from_root(newparent) {
+            Some(path) => path.trim_start_matches('/').to_string(),
+            None => {
+                reply.error(libc::ENOENT);
+                return;
+            }
+        };
+        let dest_key = format!("{dest_prefix}{newname}");
+
+        let params = PutObjectParams::default();
+        if let Err(err) = self
+            .client
+            .copy_object(&self.bucket, &source_key, &self.bucket, &dest_key, &params)
+            .await
+        {
+            error!(?err, "CopyObject failed");
+            reply.error(libc::EIO);
+            return;
+        }
+
+        if let Err(err) = self.client.delete_object(&self.bucket, &source_key).await {
+            error!(?err, "DeleteObject failed for rename source");
+            reply.error(libc::EIO);
+            return;
+        }
+
+        {
+            let mut inode_info = self.inode_info.write().unwrap();
+            if let Some(info) = inode_info.get_mut(&ino) {
+                info.name = newname.clone();
+                info.parent = newparent;
+            }
+        }
+
+        let old_parent_entries = {
+            let dir_entries = self.dir_entries.read().unwrap();
+            dir_entries.get(&parent).cloned()
+        };
+        if let Some(entries) = old_parent_entries {
+            entries.write().unwrap().remove(&name);
+        }
+
+        let new_parent_entries = {
+            let mut dir_entries = self.dir_entries.write().unwrap();
+            Arc::clone(
+                dir_entries
+                    .entry(newparent)
+                    .or_insert_with(|| Arc::new(RwLock::new(HashMap::new()))),
+            )
+        };
+        new_parent_entries.write().unwrap().insert(newname, ino);
+
+        // Invalidate the directory listing cache for both ends of the rename: the old parent's
+        // cached children still list `ino`, which no longer lives there, and the new parent's
+        // cache may predate this entry existing.
+        self.dir_listed_at.write().unwrap().remove(&parent);
+        self.dir_children_cache.write().unwrap().remove(&parent);
+        self.dir_listed_at.write().unwrap().remove(&newparent);
+        self.dir_children_cache.write().unwrap().remove(&newparent);
+
+        reply.ok();
+    }
+}
+
+impl S3Filesystem {
+    /// Upload `chunk` as the next part of the multipart upload tracked by `handle`, recording its
+    /// ETag so it can be referenced when the upload is completed.
+    async fn upload_part(&self, handle: &mut WriteHandle, chunk: Vec<u8>) -> Result<(), libc::c_int> {
+        let part_number = handle.next_part_number;
+        let upload_id = handle.upload_id.as_ref().unwrap();
+        let result = self
+            .client
+            .upload_part(
+                &handle.bucket,
+                &handle.key,
+                upload_id,
+                part_number,
+                stream::once(async move { chunk }),
+            )
+            .await
+            .map_err(|_| libc::EIO)?;
+
+        handle.completed_parts.push(CompletedPart {
+            part_number,
+            etag: result.etag,
+        });
+        handle.next_part_number += 1;
+        Ok(())
+    }
+
+    /// Best-effort cleanup of a multipart upload that could not be completed, so S3 doesn't keep
+    /// billing for the parts already uploaded.
+    async fn abort_write(&self, handle: &WriteHandle) {
+        if let Some(upload_id) = &handle.upload_id {
+            if let Err(err) = self.client.abort_multipart_upload(&handle.bucket, &handle.key, upload_id).await {
+                error!(?err, "AbortMultipartUpload failed");
+            }
+        }
+    }
 }